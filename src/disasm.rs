@@ -0,0 +1,359 @@
+use std::io::{self, Read};
+
+use crate::parser::{Export, ExportType, Expr, Func, Instr, Module, ValType};
+
+/// Parses a `.wasm` binary back into WAT text. Function and local identifiers
+/// aren't preserved in the binary format, so they're synthesized as `$fN`,
+/// `$pN` and `$lN`; this still round-trips to byte-identical WASM because the
+/// compiler only cares about declaration order, not the original names.
+pub fn disassemble(bytes: &[u8]) -> io::Result<String> {
+    let mut r = bytes;
+    read_magic_and_version(&mut r)?;
+
+    let mut types: Vec<(Vec<ValType>, Option<ValType>)> = Vec::new();
+    let mut func_type_indices: Vec<u64> = Vec::new();
+    let mut exports: Vec<Export> = Vec::new();
+    let mut code_bodies: Vec<(Vec<ValType>, Vec<u8>)> = Vec::new();
+
+    while !r.is_empty() {
+        let id = read_u8(&mut r)?;
+        let size = read_unsigned_leb128(&mut r)? as usize;
+        let (section, rest) = split_section(r, size)?;
+        r = rest;
+        match id {
+            0x01 => types = read_type_section(section)?,
+            0x03 => func_type_indices = read_func_section(section)?,
+            0x07 => exports = read_export_section(section)?,
+            0x0a => code_bodies = read_code_section(section)?,
+            _ => {}
+        }
+    }
+
+    let funcs = func_type_indices
+        .iter()
+        .zip(&code_bodies)
+        .enumerate()
+        .map(|(idx, (&type_idx, (locals, code)))| {
+            let (params, result) = &types[type_idx as usize];
+            read_func(idx, params, *result, locals, code)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let exports = exports
+        .into_iter()
+        .map(|export| Export {
+            ident: format!("f{}", export.ident),
+            ..export
+        })
+        .collect();
+
+    Ok(print_module(&Module { funcs, exports }))
+}
+
+fn read_func(
+    idx: usize,
+    params: &[ValType],
+    result: Option<ValType>,
+    locals: &[ValType],
+    code: &[u8],
+) -> io::Result<Func> {
+    let params = params
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| (format!("p{i}"), *ty))
+        .collect::<Vec<_>>();
+    let locals = locals
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| (format!("l{i}"), *ty))
+        .collect::<Vec<_>>();
+    let body = read_body(&params, &locals, code)?;
+
+    Ok(Func {
+        name: format!("f{idx}"),
+        params,
+        result,
+        locals,
+        body,
+    })
+}
+
+fn read_body(
+    params: &[(String, ValType)],
+    locals: &[(String, ValType)],
+    code: &[u8],
+) -> io::Result<Vec<Expr>> {
+    let mut r = code;
+    let mut body = Vec::new();
+    loop {
+        let opcode = read_u8(&mut r)?;
+        if opcode == 0x0b {
+            break;
+        }
+        let instr = instr_from_opcode(opcode)?;
+        body.push(Expr::Instr(instr));
+        match instr {
+            Instr::LocalGet => {
+                let idx = read_unsigned_leb128(&mut r)? as usize;
+                let ident = params
+                    .iter()
+                    .chain(locals)
+                    .nth(idx)
+                    .expect("local index out of range");
+                body.push(Expr::Ident(ident.0.clone()));
+            }
+            Instr::I32Const | Instr::I64Const => {
+                body.push(Expr::IntConst(read_signed_leb128(&mut r)?));
+            }
+            Instr::F32Const => {
+                let mut buf = [0; 4];
+                r.read_exact(&mut buf)?;
+                body.push(Expr::FloatConst(f32::from_le_bytes(buf) as f64));
+            }
+            Instr::F64Const => {
+                let mut buf = [0; 8];
+                r.read_exact(&mut buf)?;
+                body.push(Expr::FloatConst(f64::from_le_bytes(buf)));
+            }
+            Instr::I32Add => {}
+        }
+    }
+
+    Ok(body)
+}
+
+fn instr_from_opcode(opcode: u8) -> io::Result<Instr> {
+    match opcode {
+        0x20 => Ok(Instr::LocalGet),
+        0x41 => Ok(Instr::I32Const),
+        0x42 => Ok(Instr::I64Const),
+        0x43 => Ok(Instr::F32Const),
+        0x44 => Ok(Instr::F64Const),
+        0x6a => Ok(Instr::I32Add),
+        _ => Err(invalid_data(format!("unknown opcode {opcode:#x}"))),
+    }
+}
+
+fn valtype_from_byte(byte: u8) -> io::Result<ValType> {
+    match byte {
+        0x7c => Ok(ValType::F64),
+        0x7d => Ok(ValType::F32),
+        0x7e => Ok(ValType::I64),
+        0x7f => Ok(ValType::I32),
+        _ => Err(invalid_data(format!("unknown valtype {byte:#x}"))),
+    }
+}
+
+fn read_type_section(mut r: &[u8]) -> io::Result<Vec<(Vec<ValType>, Option<ValType>)>> {
+    let count = read_unsigned_leb128(&mut r)?;
+    (0..count)
+        .map(|_| {
+            let form = read_u8(&mut r)?;
+            if form != 0x60 {
+                return Err(invalid_data(format!("unknown type form {form:#x}")));
+            }
+            let params = read_valtype_vec(&mut r)?;
+            // `write_type` skips the results vector entirely (rather than writing an
+            // explicit empty one) when a func has no result, so its absence has to be
+            // inferred from what comes next: either the following type's `0x60` form
+            // byte or the end of the section.
+            let result = if r.first() == Some(&0x60) || r.is_empty() {
+                None
+            } else {
+                let results = read_valtype_vec(&mut r)?;
+                match results.len() {
+                    0 => None,
+                    1 => Some(results[0]),
+                    n => return Err(invalid_data(format!("unsupported {n} result values"))),
+                }
+            };
+            Ok((params, result))
+        })
+        .collect()
+}
+
+fn read_func_section(mut r: &[u8]) -> io::Result<Vec<u64>> {
+    let count = read_unsigned_leb128(&mut r)?;
+    (0..count).map(|_| read_unsigned_leb128(&mut r)).collect()
+}
+
+fn read_export_section(mut r: &[u8]) -> io::Result<Vec<Export>> {
+    let count = read_unsigned_leb128(&mut r)?;
+    (0..count)
+        .map(|_| {
+            let name = read_name(&mut r)?;
+            let ty = read_u8(&mut r)?;
+            let idx = read_unsigned_leb128(&mut r)?;
+            if ty != ExportType::Func as u8 {
+                return Err(invalid_data(format!("unsupported export kind {ty:#x}")));
+            }
+            Ok(Export {
+                export_name: name,
+                ident: idx.to_string(),
+                ty: ExportType::Func,
+            })
+        })
+        .collect()
+}
+
+fn read_code_section(mut r: &[u8]) -> io::Result<Vec<(Vec<ValType>, Vec<u8>)>> {
+    let count = read_unsigned_leb128(&mut r)?;
+    (0..count)
+        .map(|_| {
+            let size = read_unsigned_leb128(&mut r)? as usize;
+            let (mut body, rest) = split_section(r, size)?;
+            r = rest;
+            let locals = read_locals(&mut body)?;
+            Ok((locals, body.to_vec()))
+        })
+        .collect()
+}
+
+fn read_locals(r: &mut &[u8]) -> io::Result<Vec<ValType>> {
+    let run_count = read_unsigned_leb128(r)?;
+    let mut locals = Vec::new();
+    for _ in 0..run_count {
+        let count = read_unsigned_leb128(r)?;
+        let ty = valtype_from_byte(read_u8(r)?)?;
+        locals.extend(vec![ty; count as usize]);
+    }
+
+    Ok(locals)
+}
+
+fn read_valtype_vec(r: &mut &[u8]) -> io::Result<Vec<ValType>> {
+    let count = read_unsigned_leb128(r)?;
+    (0..count)
+        .map(|_| valtype_from_byte(read_u8(r)?))
+        .collect()
+}
+
+fn read_name(r: &mut &[u8]) -> io::Result<String> {
+    let len = read_unsigned_leb128(r)? as usize;
+    let (bytes, rest) = split_section(r, len)?;
+    *r = rest;
+    String::from_utf8(bytes.to_vec()).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn read_magic_and_version(r: &mut &[u8]) -> io::Result<()> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != b"\0asm" {
+        return Err(invalid_data("not a WASM binary"));
+    }
+    let mut version = [0; 4];
+    r.read_exact(&mut version)?;
+
+    Ok(())
+}
+
+fn split_section(r: &[u8], len: usize) -> io::Result<(&[u8], &[u8])> {
+    if len > r.len() {
+        return Err(invalid_data("section length out of bounds"));
+    }
+    Ok(r.split_at(len))
+}
+
+fn read_u8(r: &mut &[u8]) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_unsigned_leb128(r: &mut &[u8]) -> io::Result<u64> {
+    leb128::read::unsigned(r).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn read_signed_leb128(r: &mut &[u8]) -> io::Result<i64> {
+    leb128::read::signed(r).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn print_module(module: &Module) -> String {
+    let mut out = String::from("(module\n");
+    for func in &module.funcs {
+        print_func(&mut out, func);
+    }
+    for export in &module.exports {
+        out.push_str(&format!(
+            "  (export \"{}\" (func ${}))\n",
+            export.export_name, export.ident
+        ));
+    }
+    out.push(')');
+
+    out
+}
+
+fn print_func(out: &mut String, func: &Func) {
+    out.push_str(&format!("  (func ${}", func.name));
+    for (name, ty) in &func.params {
+        out.push_str(&format!(" (param ${name} {})", print_valtype(*ty)));
+    }
+    if let Some(result) = func.result {
+        out.push_str(&format!(" (result {})", print_valtype(result)));
+    }
+    out.push('\n');
+    for (name, ty) in &func.locals {
+        out.push_str(&format!("    (local ${name} {})\n", print_valtype(*ty)));
+    }
+    print_body(out, &func.body);
+    out.push_str("  )\n");
+}
+
+// Pairs each instruction with its immediate operand (if any) on one line, mirroring
+// how `write_code` walks the same flat `Expr` sequence during compilation.
+fn print_body(out: &mut String, body: &[Expr]) {
+    let mut exprs = body.iter().peekable();
+    while let Some(expr) = exprs.next() {
+        let instr = match expr {
+            Expr::Instr(instr) => instr,
+            _ => unimplemented!(),
+        };
+        out.push_str("    ");
+        out.push_str(print_instr(*instr));
+        if matches!(
+            exprs.peek(),
+            Some(Expr::Ident(_) | Expr::IntConst(_) | Expr::FloatConst(_))
+        ) {
+            out.push(' ');
+            print_operand(out, exprs.next().unwrap());
+        }
+        out.push('\n');
+    }
+}
+
+fn print_operand(out: &mut String, expr: &Expr) {
+    match expr {
+        Expr::Ident(ident) => out.push_str(&format!("${ident}")),
+        Expr::IntConst(n) => out.push_str(&n.to_string()),
+        // Debug formatting always keeps a decimal point or switches to scientific
+        // notation (`1` -> "1.0", `1e30` stays exponential) and spells out `inf`/
+        // `NaN`; `float_literal` accepts all of those forms so this round-trips.
+        Expr::FloatConst(n) => out.push_str(&format!("{n:?}")),
+        Expr::Instr(_) | Expr::Func(_) | Expr::Export(_) | Expr::Folded(..) => unimplemented!(),
+    }
+}
+
+fn print_instr(instr: Instr) -> &'static str {
+    match instr {
+        Instr::LocalGet => "local.get",
+        Instr::I32Const => "i32.const",
+        Instr::I64Const => "i64.const",
+        Instr::F32Const => "f32.const",
+        Instr::F64Const => "f64.const",
+        Instr::I32Add => "i32.add",
+    }
+}
+
+fn print_valtype(ty: ValType) -> &'static str {
+    match ty {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+    }
+}
@@ -1,10 +1,11 @@
-use std::{fs, io};
+use std::fs;
 
 use nom::{error::convert_error, Finish};
-use parser::{ExportType, Expr, Func, Module};
+use parser::{ExportType, Expr, Func, Instr, Module, ValType};
 
 use crate::parser::module;
 
+mod disasm;
 mod parser;
 
 #[allow(unused)]
@@ -27,20 +28,29 @@ enum Section {
 
 fn main() {
     let path = std::env::args().nth(1).expect("must specify input file");
+    if path.ends_with(".wasm") {
+        let out_file_path = format!("{}wat", path.strip_suffix("wasm").unwrap());
+        let input = fs::read(&path).expect("invalid path");
+        let wat = disasm::disassemble(&input).expect("failed to disassemble");
+        fs::write(out_file_path, wat).unwrap();
+        return;
+    }
+
     let mut out_file_path = path.split_once("wat").unwrap().0.to_owned();
     out_file_path.push_str("wasm");
     let input = fs::read_to_string(&path).expect("invalid path");
     match module(&input).finish() {
         Ok((_, module)) => {
-            let wasm = compile(&module).unwrap();
+            let wasm = compile(&module);
             fs::write(&out_file_path, wasm).unwrap();
         }
         Err(e) => eprintln!("{}", convert_error(input.as_str(), e)),
     }
 }
 
-fn compile(module: &Module) -> io::Result<Vec<u8>> {
+fn compile(module: &Module) -> Vec<u8> {
     let mut export_sec = Vec::new();
+    let export_count_pos = reserve_len_prefix(&mut export_sec);
     for export in &module.exports {
         let idx = match export.ty {
             ExportType::Func => {
@@ -54,39 +64,88 @@ fn compile(module: &Module) -> io::Result<Vec<u8>> {
             }
         };
 
-        write_export(&mut export_sec, &export.ident, export.ty, idx)?;
+        write_export(&mut export_sec, &export.export_name, export.ty, idx);
     }
-    into_wasm_vec(&mut export_sec, module.exports.len())?;
+    patch_len_prefix(&mut export_sec, export_count_pos, module.exports.len() as u64);
 
     let mut func_sec = Vec::new();
     let mut type_sec = Vec::new();
     let mut code_sec = Vec::new();
+    let func_count_pos = reserve_len_prefix(&mut func_sec);
+    let type_count_pos = reserve_len_prefix(&mut type_sec);
+    let code_count_pos = reserve_len_prefix(&mut code_sec);
     for (idx, func) in module.funcs.iter().enumerate() {
-        leb128::write::unsigned(&mut func_sec, idx as u64)?;
-        write_type(&mut type_sec, func)?;
-        write_code(&mut code_sec, func)?;
+        append_unsigned_leb128(&mut func_sec, idx as u64);
+        write_type(&mut type_sec, func);
+        write_code(&mut code_sec, func);
     }
-    let funcs_len = module.funcs.len();
-    into_wasm_vec(&mut func_sec, funcs_len)?;
-    into_wasm_vec(&mut type_sec, funcs_len)?;
-    into_wasm_vec(&mut code_sec, funcs_len)?;
+    let funcs_len = module.funcs.len() as u64;
+    patch_len_prefix(&mut func_sec, func_count_pos, funcs_len);
+    patch_len_prefix(&mut type_sec, type_count_pos, funcs_len);
+    patch_len_prefix(&mut code_sec, code_count_pos, funcs_len);
 
     let mut wasm = Vec::new();
     write_magic_and_version(&mut wasm);
-    write_section(&mut wasm, Section::Type, &type_sec)?;
-    write_section(&mut wasm, Section::Func, &func_sec)?;
-    write_section(&mut wasm, Section::Export, &export_sec)?;
-    write_section(&mut wasm, Section::Code, &code_sec)?;
+    write_section(&mut wasm, Section::Type, &type_sec);
+    write_section(&mut wasm, Section::Func, &func_sec);
+    write_section(&mut wasm, Section::Export, &export_sec);
+    write_section(&mut wasm, Section::Code, &code_sec);
 
-    Ok(wasm)
+    wasm
 }
 
-fn into_wasm_vec(vec: &mut Vec<u8>, len: usize) -> io::Result<()> {
-    let mut len_bytes = Vec::new();
-    leb128::write::unsigned(&mut len_bytes, len as u64)?;
-    vec.splice(0..0, len_bytes);
+/// Writes `value` as unsigned LEB128 at `position`, growing `out` when `position`
+/// is at (or beyond) its current end. Returns the number of bytes written.
+fn write_unsigned_leb128(out: &mut Vec<u8>, position: usize, value: u64) -> usize {
+    // u64::BITS / 7, rounded up: the widest a u64 can ever encode to.
+    let mut buf = [0u8; 10];
+    let mut value = value;
+    let mut len = 0;
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        buf[len] = byte;
+        len += 1;
+        if value == 0 {
+            break;
+        }
+    }
+
+    if position + len > out.len() {
+        out.resize(position + len, 0);
+    }
+    out[position..position + len].copy_from_slice(&buf[..len]);
 
-    Ok(())
+    len
+}
+
+/// Appends `value` as unsigned LEB128 to the end of `out`.
+fn append_unsigned_leb128(out: &mut Vec<u8>, value: u64) {
+    let pos = out.len();
+    write_unsigned_leb128(out, pos, value);
+}
+
+/// Reserves the maximum-width (5 byte) placeholder for a u32 LEB128 length prefix,
+/// to be filled in later by `patch_len_prefix` once the prefixed content is known.
+fn reserve_len_prefix(out: &mut Vec<u8>) -> usize {
+    let pos = out.len();
+    out.resize(pos + 5, 0);
+
+    pos
+}
+
+/// Back-patches a length prefix reserved with `reserve_len_prefix`, closing the gap
+/// left behind when the true encoding is shorter than the reserved 5 bytes.
+fn patch_len_prefix(out: &mut Vec<u8>, pos: usize, len: u64) {
+    let written = write_unsigned_leb128(out, pos, len);
+    if written < 5 {
+        let gap = 5 - written;
+        out.copy_within(pos + 5.., pos + written);
+        out.truncate(out.len() - gap);
+    }
 }
 
 fn write_magic_and_version(vec: &mut Vec<u8>) {
@@ -96,73 +155,233 @@ fn write_magic_and_version(vec: &mut Vec<u8>) {
     vec.extend_from_slice(&version.to_le_bytes());
 }
 
-fn write_export(vec: &mut Vec<u8>, name: &str, ty: ExportType, idx: usize) -> io::Result<()> {
-    let mut name_bytes = Vec::from(name.as_bytes());
-    into_wasm_vec(&mut name_bytes, name.len())?;
-    vec.extend_from_slice(&name_bytes);
-    vec.push(ty as u8);
-    leb128::write::unsigned(vec, idx as u64)?;
+fn write_export(vec: &mut Vec<u8>, name: &str, ty: ExportType, idx: usize) {
+    let name_len_pos = reserve_len_prefix(vec);
+    vec.extend_from_slice(name.as_bytes());
+    patch_len_prefix(vec, name_len_pos, name.len() as u64);
 
-    Ok(())
+    vec.push(ty as u8);
+    append_unsigned_leb128(vec, idx as u64);
 }
 
-fn write_type(vec: &mut Vec<u8>, func: &Func) -> io::Result<()> {
+fn write_type(vec: &mut Vec<u8>, func: &Func) {
     vec.push(0x60);
-    let params_len = func.params.len();
-    let mut types = Vec::new();
+
+    let params_len_pos = reserve_len_prefix(vec);
     for (_, param) in &func.params {
-        leb128::write::unsigned(&mut types, *param as u64)?;
+        append_unsigned_leb128(vec, *param as u64);
     }
-    into_wasm_vec(&mut types, params_len)?;
-    vec.extend_from_slice(&types);
+    patch_len_prefix(vec, params_len_pos, func.params.len() as u64);
 
     if let Some(result) = func.result {
-        let mut results = Vec::new();
-        leb128::write::unsigned(&mut results, result as u64)?;
-        into_wasm_vec(&mut results, 1)?;
-        vec.extend_from_slice(&results);
+        let results_len_pos = reserve_len_prefix(vec);
+        append_unsigned_leb128(vec, result as u64);
+        patch_len_prefix(vec, results_len_pos, 1);
     }
-
-    Ok(())
 }
 
-fn write_code(vec: &mut Vec<u8>, func: &Func) -> io::Result<()> {
-    // TODO: Properly handle declaration of locals
-    let mut locals = Vec::new();
-    // create empty `locals` vec
-    into_wasm_vec(&mut locals, 0)?;
+fn write_code(vec: &mut Vec<u8>, func: &Func) {
+    let size_pos = reserve_len_prefix(vec);
 
-    let mut code = Vec::new();
-    for expr in &func.body {
+    write_locals(vec, &func.locals);
+
+    let mut flat = Vec::new();
+    flatten_body(&func.body, &mut flat);
+
+    let mut last_instr = None;
+    for expr in &flat {
         match expr {
-            Expr::Instr(instr) => code.push(*instr as u8),
-            Expr::Ident(ident) => {
+            FlatExpr::Instr(instr) => {
+                vec.push(*instr as u8);
+                last_instr = Some(*instr);
+            }
+            FlatExpr::Ident(ident) => {
                 let idx = func
                     .params
                     .iter()
-                    .position(|p| &p.0 == ident)
+                    .chain(&func.locals)
+                    .position(|p| p.0 == *ident)
                     .expect("unknown ident");
 
-                leb128::write::unsigned(&mut code, idx as u64)?;
+                append_unsigned_leb128(vec, idx as u64);
             }
-            _ => unimplemented!(),
+            FlatExpr::IntConst(n) => match last_instr {
+                Some(Instr::I32Const) => write_signed_leb128(vec, *n as i32 as i64),
+                Some(Instr::I64Const) => write_signed_leb128(vec, *n),
+                _ => unimplemented!(),
+            },
+            FlatExpr::FloatConst(n) => match last_instr {
+                Some(Instr::F32Const) => vec.extend_from_slice(&(*n as f32).to_le_bytes()),
+                Some(Instr::F64Const) => vec.extend_from_slice(&n.to_le_bytes()),
+                _ => unimplemented!(),
+            },
         }
     }
     // end
-    code.push(0x0b);
+    vec.push(0x0b);
+
+    patch_len_prefix(vec, size_pos, (vec.len() - (size_pos + 5)) as u64);
+}
+
+/// A single flat stack-machine operation, post-flattening of any folded
+/// (nested s-expression) instructions.
+enum FlatExpr<'e> {
+    Instr(Instr),
+    Ident(&'e str),
+    IntConst(i64),
+    FloatConst(f64),
+}
+
+/// Lowers a function body to post-order stack-machine order: a folded
+/// instruction's operands are flattened first, then its own opcode follows.
+/// The flat form (bare instructions and idents) is left untouched, so the
+/// two syntaxes can be mixed freely.
+fn flatten_body<'e>(body: &'e [Expr], out: &mut Vec<FlatExpr<'e>>) {
+    for expr in body {
+        flatten_expr(expr, out);
+    }
+}
 
-    let size = locals.len() + code.len();
-    leb128::write::unsigned(vec, size as u64)?;
-    vec.extend_from_slice(&locals);
-    vec.extend_from_slice(&code);
+fn flatten_expr<'e>(expr: &'e Expr, out: &mut Vec<FlatExpr<'e>>) {
+    match expr {
+        Expr::Instr(instr) => out.push(FlatExpr::Instr(*instr)),
+        Expr::Ident(ident) => out.push(FlatExpr::Ident(ident)),
+        Expr::IntConst(n) => out.push(FlatExpr::IntConst(*n)),
+        Expr::FloatConst(n) => out.push(FlatExpr::FloatConst(*n)),
+        // Operands are either an immediate attached to this instruction
+        // (`local.get`'s ident, a const's literal) or nested instructions
+        // that produce the stack values this one consumes. Immediates are
+        // written right after the opcode, just like in the flat form;
+        // stack operands are flattened first, in post-order.
+        Expr::Folded(instr, operands) => {
+            let is_immediate = |e: &Expr| {
+                matches!(
+                    e,
+                    Expr::Ident(_) | Expr::IntConst(_) | Expr::FloatConst(_)
+                )
+            };
+            if operands.iter().all(is_immediate) {
+                out.push(FlatExpr::Instr(*instr));
+                for operand in operands {
+                    flatten_expr(operand, out);
+                }
+            } else {
+                for operand in operands {
+                    flatten_expr(operand, out);
+                }
+                out.push(FlatExpr::Instr(*instr));
+            }
+        }
+        Expr::Func(_) | Expr::Export(_) => unimplemented!(),
+    }
+}
 
-    Ok(())
+/// Encodes a function's locals as a vector of `(count, valtype)` pairs, coalescing
+/// runs of the same type into a single entry.
+fn write_locals(vec: &mut Vec<u8>, locals: &[(String, ValType)]) {
+    let mut runs: Vec<(u64, ValType)> = Vec::new();
+    for (_, ty) in locals {
+        match runs.last_mut() {
+            Some((count, last_ty)) if *last_ty == *ty => *count += 1,
+            _ => runs.push((1, *ty)),
+        }
+    }
+
+    let runs_len_pos = reserve_len_prefix(vec);
+    for (count, ty) in &runs {
+        append_unsigned_leb128(vec, *count);
+        vec.push(*ty as u8);
+    }
+    patch_len_prefix(vec, runs_len_pos, runs.len() as u64);
 }
 
-fn write_section(vec: &mut Vec<u8>, ty: Section, bytes: &[u8]) -> io::Result<()> {
+/// Writes `value` as a signed LEB128 integer, as required for WebAssembly's
+/// sign-extended constants (`leb128::write::unsigned` would corrupt negative
+/// values and values with the high bit set).
+fn write_signed_leb128(vec: &mut Vec<u8>, value: i64) {
+    let mut value = value;
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if !done {
+            byte |= 0x80;
+        }
+        vec.push(byte);
+        if done {
+            break;
+        }
+    }
+}
+
+fn write_section(vec: &mut Vec<u8>, ty: Section, bytes: &[u8]) {
     vec.push(ty as u8);
-    leb128::write::unsigned(vec, bytes.len() as u64)?;
+    append_unsigned_leb128(vec, bytes.len() as u64);
     vec.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use nom::{error::VerboseError, Finish};
 
-    Ok(())
+    use super::*;
+
+    const WAT: &str = r#"
+        (module
+          (func $add (param $a i32) (param $b i32) (result i32)
+            local.get $a
+            local.get $b
+            i32.add)
+          (export "add" (func $add)))
+    "#;
+
+    #[test]
+    fn wasm_to_wat_to_wasm_round_trips() {
+        let (_, module) = parser::module::<VerboseError<&str>>(WAT).finish().unwrap();
+        let wasm = compile(&module);
+
+        let wat = disasm::disassemble(&wasm).unwrap();
+        let (_, module) = parser::module::<VerboseError<&str>>(&wat).finish().unwrap();
+        let wasm_again = compile(&module);
+
+        assert_eq!(wasm, wasm_again);
+    }
+
+    #[test]
+    fn folded_instructions_match_flat_form() {
+        const FOLDED_WAT: &str = r#"
+            (module
+              (func $add (param $a i32) (param $b i32) (result i32)
+                (i32.add (local.get $a) (local.get $b)))
+              (export "add" (func $add)))
+        "#;
+
+        let (_, flat) = parser::module::<VerboseError<&str>>(WAT).finish().unwrap();
+        let (_, folded) = parser::module::<VerboseError<&str>>(FOLDED_WAT)
+            .finish()
+            .unwrap();
+
+        assert_eq!(compile(&flat), compile(&folded));
+    }
+
+    #[test]
+    fn float_const_round_trips() {
+        const FLOAT_WAT: &str = r#"
+            (module
+              (func $big (result f64)
+                f64.const 1e30)
+              (export "big" (func $big)))
+        "#;
+
+        let (_, module) = parser::module::<VerboseError<&str>>(FLOAT_WAT)
+            .finish()
+            .unwrap();
+        let wasm = compile(&module);
+
+        let wat = disasm::disassemble(&wasm).unwrap();
+        let (_, module) = parser::module::<VerboseError<&str>>(&wat).finish().unwrap();
+        let wasm_again = compile(&module);
+
+        assert_eq!(wasm, wasm_again);
+    }
 }
@@ -2,12 +2,12 @@ use std::{num::ParseIntError, str::FromStr};
 
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag, take_until, take_while},
+    bytes::complete::{is_not, tag, tag_no_case, take_until, take_while},
     character::complete::{alpha1, alphanumeric1, char, digit1, one_of},
-    combinator::{eof, map, map_res, opt, recognize, value},
+    combinator::{eof, map, map_res, opt, peek, recognize, value},
     error::{FromExternalError, ParseError},
     multi::{many0, many0_count},
-    number::complete::float,
+    number::complete::double,
     sequence::{delimited, pair, preceded, tuple},
     IResult,
 };
@@ -19,9 +19,15 @@ pub enum Expr {
     Export(Export),
     Instr(Instr),
     Ident(String),
+    IntConst(i64),
+    FloatConst(f64),
+    /// A folded instruction, e.g. `(i32.add (local.get $a) (local.get $b))`.
+    /// Operands are nested `Expr`s rather than a flat sequence, and get
+    /// lowered to post-order stack-machine order in `write_code`.
+    Folded(Instr, Vec<Expr>),
 }
 
-#[derive(Debug, EnumString, Clone, Copy)]
+#[derive(Debug, EnumString, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum ValType {
     #[strum(serialize = "f64")]
@@ -39,6 +45,14 @@ pub enum ValType {
 pub enum Instr {
     #[strum(serialize = "local.get")]
     LocalGet = 0x20,
+    #[strum(serialize = "i32.const")]
+    I32Const = 0x41,
+    #[strum(serialize = "i64.const")]
+    I64Const,
+    #[strum(serialize = "f32.const")]
+    F32Const,
+    #[strum(serialize = "f64.const")]
+    F64Const,
     #[strum(serialize = "i32.add")]
     I32Add = 0x6a,
 }
@@ -48,6 +62,7 @@ pub struct Func {
     pub name: String,
     pub params: Vec<(String, ValType)>,
     pub result: Option<ValType>,
+    pub locals: Vec<(String, ValType)>,
     pub body: Vec<Expr>,
 }
 
@@ -71,7 +86,12 @@ pub struct Module {
     pub exports: Vec<Export>,
 }
 
-pub fn module<'a, E: ParseError<&'a str> + FromExternalError<&'a str, strum::ParseError>>(
+pub fn module<
+    'a,
+    E: ParseError<&'a str>
+        + FromExternalError<&'a str, strum::ParseError>
+        + FromExternalError<&'a str, ParseIntError>,
+>(
     i: &'a str,
 ) -> IResult<&'a str, Module, E> {
     let mut module = Module::default();
@@ -90,18 +110,45 @@ pub fn module<'a, E: ParseError<&'a str> + FromExternalError<&'a str, strum::Par
     Ok((i, module))
 }
 
-fn expr<'a, E: ParseError<&'a str> + FromExternalError<&'a str, strum::ParseError>>(
+fn expr<
+    'a,
+    E: ParseError<&'a str>
+        + FromExternalError<&'a str, strum::ParseError>
+        + FromExternalError<&'a str, ParseIntError>,
+>(
     i: &'a str,
 ) -> IResult<&'a str, Expr, E> {
     alt((
         map(func, Expr::Func),
         map(export, Expr::Export),
+        folded_instr,
         map(instr, Expr::Instr),
+        map(float_literal, Expr::FloatConst),
+        map(signed_integer, Expr::IntConst),
         map(identifier, |s| Expr::Ident(s.to_string())),
     ))(i)
 }
 
-fn func<'a, E: ParseError<&'a str> + FromExternalError<&'a str, strum::ParseError>>(
+fn folded_instr<
+    'a,
+    E: ParseError<&'a str>
+        + FromExternalError<&'a str, strum::ParseError>
+        + FromExternalError<&'a str, ParseIntError>,
+>(
+    i: &'a str,
+) -> IResult<&'a str, Expr, E> {
+    map(
+        s_expr(pair(ws(instr), many0(ws(expr)))),
+        |(instr, operands)| Expr::Folded(instr, operands),
+    )(i)
+}
+
+fn func<
+    'a,
+    E: ParseError<&'a str>
+        + FromExternalError<&'a str, strum::ParseError>
+        + FromExternalError<&'a str, ParseIntError>,
+>(
     i: &'a str,
 ) -> IResult<&'a str, Func, E> {
     map(
@@ -109,12 +156,14 @@ fn func<'a, E: ParseError<&'a str> + FromExternalError<&'a str, strum::ParseErro
             preceded(ws(tag("func")), identifier),
             many0(ws(param)),
             ws(opt(result)),
+            many0(ws(local)),
             many0(ws(expr)),
         ))),
-        |(name, params, result, body)| Func {
+        |(name, params, result, locals, body)| Func {
             name: name.to_string(),
             params,
             result,
+            locals,
             body,
         },
     )(i)
@@ -135,6 +184,15 @@ fn param<'a, E: ParseError<&'a str> + FromExternalError<&'a str, strum::ParseErr
     )(i)
 }
 
+fn local<'a, E: ParseError<&'a str> + FromExternalError<&'a str, strum::ParseError>>(
+    i: &'a str,
+) -> IResult<&'a str, (String, ValType), E> {
+    map(
+        s_expr(tuple((preceded(ws(tag("local")), identifier), ws(valtype)))),
+        |(name, ty)| (name.to_string(), ty),
+    )(i)
+}
+
 fn result<'a, E: ParseError<&'a str> + FromExternalError<&'a str, strum::ParseError>>(
     i: &'a str,
 ) -> IResult<&'a str, ValType, E> {
@@ -194,14 +252,30 @@ fn comment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, (), E> {
     alt((linecomment, blockcomment))(i)
 }
 
-fn integer<'a, E: ParseError<&'a str> + FromExternalError<&'a str, ParseIntError>>(
+fn signed_integer<'a, E: ParseError<&'a str> + FromExternalError<&'a str, ParseIntError>>(
     i: &'a str,
-) -> IResult<&'a str, i32, E> {
-    map_res(recognize(digit1), str::parse)(i)
+) -> IResult<&'a str, i64, E> {
+    map_res(recognize(pair(opt(char('-')), digit1)), str::parse)(i)
 }
 
-fn hexfloat<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, f32, E> {
-    preceded(tag("0x"), float)(i)
+// Only matches tokens with a decimal point, an exponent, or `inf`/`nan`, so plain
+// integers fall through to `signed_integer`. The leading sign is peeled off by hand
+// because `nom`'s `double` doesn't accept one in front of `inf`/`nan`.
+fn float_literal<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, f64, E> {
+    let (i, _) = peek(pair(
+        opt(char('-')),
+        alt((
+            recognize(tuple((digit1, char('.'), digit1))),
+            recognize(pair(digit1, one_of("eE"))),
+            tag_no_case("infinity"),
+            tag_no_case("inf"),
+            tag_no_case("nan"),
+        )),
+    ))(i)?;
+    let (i, negative) = map(opt(char('-')), |s| s.is_some())(i)?;
+    let (i, f) = double(i)?;
+
+    Ok((i, if negative { -f } else { f }))
 }
 
 // TODO: Improve string parsing